@@ -0,0 +1,141 @@
+use crate::error::Error;
+use crate::globals::GLOBALS;
+use crate::nip46::ParsedCommand;
+use nostr_types::{PublicKey, RelayUrl};
+use serde::{Deserialize, Serialize};
+
+/// A persisted approval rule, consulted before a prompt is shown to the user.
+///
+/// Rules let a user avoid re-deciding the same thing every session, e.g.
+/// "always approve `get_public_key` from this remote signer", "auto-allow
+/// AUTH on this relay", or "deny signing kind N".
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ApprovalRule {
+    /// Approve or deny a NIP-46 method from a particular remote signer.
+    Nip46Method {
+        signer: PublicKey,
+        method: String,
+        approve: bool,
+    },
+
+    /// Approve or deny signing a particular event kind for a remote signer.
+    Nip46SignKind {
+        signer: PublicKey,
+        kind: u32,
+        approve: bool,
+    },
+
+    /// Approve or deny relay AUTH on a particular relay.
+    RelayAuth { relay: RelayUrl, approve: bool },
+
+    /// Approve or deny connecting to a particular relay.
+    RelayConnect { relay: RelayUrl, approve: bool },
+}
+
+/// The outcome of consulting the stored policy for a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// A rule approves the request; act without prompting.
+    Approve,
+    /// A rule denies the request; drop it without prompting.
+    Deny,
+    /// No rule matched; the user must be prompted.
+    Ask,
+}
+
+impl From<bool> for Decision {
+    fn from(approve: bool) -> Decision {
+        if approve {
+            Decision::Approve
+        } else {
+            Decision::Deny
+        }
+    }
+}
+
+/// The approval-policy layer. Stateless; all rules live in [`Storage`].
+///
+/// [`Storage`]: crate::storage::Storage
+#[derive(Debug, Default)]
+pub struct ApprovalPolicy {}
+
+impl ApprovalPolicy {
+    pub fn new() -> ApprovalPolicy {
+        Default::default()
+    }
+
+    /// Consult the stored policy for a NIP-46 command from a remote signer.
+    pub fn consult_nip46(&self, signer: PublicKey, command: &ParsedCommand) -> Decision {
+        for rule in self.rules() {
+            match rule {
+                ApprovalRule::Nip46Method {
+                    signer: s,
+                    method,
+                    approve,
+                } if s == signer && method == command.method => return approve.into(),
+                ApprovalRule::Nip46SignKind {
+                    signer: s,
+                    kind,
+                    approve,
+                } if s == signer
+                    && command.method == "sign_event"
+                    && command_signs_kind(command, kind) =>
+                {
+                    return approve.into()
+                }
+                _ => {}
+            }
+        }
+        Decision::Ask
+    }
+
+    /// Consult the stored policy for a relay AUTH request.
+    pub fn consult_auth(&self, relay: &RelayUrl) -> Decision {
+        for rule in self.rules() {
+            if let ApprovalRule::RelayAuth { relay: r, approve } = rule {
+                if &r == relay {
+                    return approve.into();
+                }
+            }
+        }
+        Decision::Ask
+    }
+
+    /// Consult the stored policy for a relay connect request.
+    pub fn consult_connect(&self, relay: &RelayUrl) -> Decision {
+        for rule in self.rules() {
+            if let ApprovalRule::RelayConnect { relay: r, approve } = rule {
+                if &r == relay {
+                    return approve.into();
+                }
+            }
+        }
+        Decision::Ask
+    }
+
+    /// Persist a rule so future matching requests are decided automatically.
+    pub fn remember(&self, rule: ApprovalRule) -> Result<(), Error> {
+        GLOBALS.storage.add_approval_rule(rule)
+    }
+
+    /// Forget a previously persisted rule.
+    pub fn forget(&self, rule: &ApprovalRule) -> Result<(), Error> {
+        GLOBALS.storage.remove_approval_rule(rule)
+    }
+
+    /// All persisted rules, for the UI to display and edit.
+    pub fn rules(&self) -> Vec<ApprovalRule> {
+        GLOBALS.storage.read_approval_rules().unwrap_or_default()
+    }
+}
+
+/// Whether a `sign_event` command is signing the given kind.
+fn command_signs_kind(command: &ParsedCommand, kind: u32) -> bool {
+    command
+        .params
+        .first()
+        .and_then(|p| serde_json::from_str::<serde_json::Value>(p).ok())
+        .and_then(|v| v.get("kind").and_then(|k| k.as_u64()))
+        .map(|k| k as u32 == kind)
+        .unwrap_or(false)
+}
@@ -0,0 +1,199 @@
+use crate::globals::GLOBALS;
+use dashmap::DashMap;
+use nostr_types::Event;
+use parking_lot::RwLock as PRwLock;
+use rhai::{Dynamic, Engine, Scope, AST};
+use std::time::SystemTime;
+
+/// The filter name used when none has been selected.
+pub const DEFAULT_FILTER: &str = "home";
+
+/// A compiled content-filter script.
+struct CompiledFilter {
+    ast: AST,
+}
+
+/// Manages the Rhai content-filter scripts.
+///
+/// Scripts are compiled once into an [`AST`] and re-used across feed draws.
+/// Each named script (e.g. `"home"` vs. `"global"`) can be selected per
+/// feed; the manager watches the backing files and recompiles on change
+/// without a restart, reporting compile errors into the status queue rather
+/// than failing silently.
+pub struct FilterManager {
+    engine: Engine,
+    scripts: DashMap<String, CompiledFilter>,
+    /// Every name we have tried to load, mapped to the backing file's mtime as
+    /// observed at that attempt (`None` if the file was absent or unreadable).
+    /// This keeps a missing script from being re-read on every feed draw while
+    /// still letting [`FilterManager::reload_changed`] re-probe names whose
+    /// first load failed, so a fixed or newly-created script is picked up live.
+    attempted: DashMap<String, Option<SystemTime>>,
+    active: PRwLock<String>,
+}
+
+impl FilterManager {
+    /// Create a manager.
+    ///
+    /// This does **not** touch [`GLOBALS`]: it is constructed from within the
+    /// `GLOBALS` `lazy_static` initializer, where reaching back into `GLOBALS`
+    /// would re-enter the `Once` and deadlock. Scripts are compiled lazily on
+    /// first use (see [`FilterManager::load`]), once `GLOBALS` exists.
+    pub fn new() -> FilterManager {
+        FilterManager {
+            engine: Engine::new(),
+            scripts: DashMap::new(),
+            attempted: DashMap::new(),
+            active: PRwLock::new(DEFAULT_FILTER.to_owned()),
+        }
+    }
+
+    /// Ensure a named script has been loaded at least once, reading it from
+    /// storage on the first request. Safe to call on the hot feed path.
+    fn ensure_loaded(&self, name: &str) {
+        if !self.attempted.contains_key(name) {
+            self.load(name);
+        }
+    }
+
+    /// The current mtime of a named script's backing file, if any.
+    fn script_mtime(name: &str) -> Option<SystemTime> {
+        GLOBALS
+            .storage
+            .filter_script_path(name)
+            .ok()
+            .flatten()
+            .and_then(|p| p.metadata().ok())
+            .and_then(|m| m.modified().ok())
+    }
+
+    /// The name of the filter currently applied to the feed.
+    pub fn active_name(&self) -> String {
+        self.active.read().clone()
+    }
+
+    /// Switch the active filter. Compiles the script if we have not yet.
+    pub fn set_active<S: Into<String>>(&self, name: S) {
+        let name = name.into();
+        self.ensure_loaded(&name);
+        *self.active.write() = name;
+    }
+
+    /// The names of all known filter scripts, for the UI to offer.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = GLOBALS
+            .storage
+            .filter_script_names()
+            .unwrap_or_default();
+        for entry in self.scripts.iter() {
+            if !names.contains(entry.key()) {
+                names.push(entry.key().clone());
+            }
+        }
+        names
+    }
+
+    /// Load (or reload) a named script from storage, compiling it into an
+    /// `AST`. Compile errors are reported into the status queue and the
+    /// previous compilation (if any) is left in place.
+    pub fn load(&self, name: &str) {
+        // Record the mtime we are loading against regardless of the outcome, so
+        // reload_changed can tell when a missing or broken script later
+        // appears or is fixed on disk.
+        self.attempted
+            .insert(name.to_owned(), Self::script_mtime(name));
+
+        let source = match GLOBALS.storage.read_filter_script(name) {
+            Ok(Some(source)) => source,
+            Ok(None) => return,
+            Err(e) => {
+                GLOBALS
+                    .status_queue
+                    .write()
+                    .write(format!("Filter '{name}' could not be read: {e}"));
+                return;
+            }
+        };
+
+        match self.engine.compile(&source) {
+            Ok(ast) => {
+                self.scripts.insert(name.to_owned(), CompiledFilter { ast });
+            }
+            Err(e) => {
+                GLOBALS
+                    .status_queue
+                    .write()
+                    .write(format!("Filter '{name}' failed to compile: {e}"));
+            }
+        }
+    }
+
+    /// Poll the backing files of every script we have tried to load and
+    /// recompile any that have changed on disk. This includes names whose first
+    /// load failed (syntax error) or whose file did not yet exist, so a fixed
+    /// or newly-created script is picked up live without a restart. Intended to
+    /// be called from the periodic tick.
+    pub fn reload_changed(&self) {
+        let stale: Vec<String> = self
+            .attempted
+            .iter()
+            .filter(|e| Self::script_mtime(e.key()) != *e.value())
+            .map(|e| e.key().clone())
+            .collect();
+
+        for name in stale {
+            self.load(&name);
+        }
+    }
+
+    /// Evaluate the named filter against an event, returning whether the
+    /// event should be shown. Unknown or uncompiled filters admit everything.
+    pub fn evaluate(&self, name: &str, event: &Event) -> bool {
+        self.ensure_loaded(name);
+        let compiled = match self.scripts.get(name) {
+            Some(c) => c,
+            None => return true,
+        };
+
+        let mut scope = Scope::new();
+        scope.push("event", event_map(event));
+
+        match self
+            .engine
+            .eval_ast_with_scope::<bool>(&mut scope, &compiled.ast)
+        {
+            Ok(keep) => keep,
+            Err(e) => {
+                GLOBALS
+                    .status_queue
+                    .write()
+                    .write(format!("Filter '{name}' errored: {e}"));
+                true
+            }
+        }
+    }
+
+    /// Evaluate the currently active filter against an event.
+    pub fn filter(&self, event: &Event) -> bool {
+        self.evaluate(&self.active_name(), event)
+    }
+}
+
+impl Default for FilterManager {
+    fn default() -> FilterManager {
+        FilterManager::new()
+    }
+}
+
+/// Build the `event` map exposed to filter scripts.
+fn event_map(event: &Event) -> rhai::Map {
+    let mut map = rhai::Map::new();
+    map.insert("id".into(), Dynamic::from(event.id.as_hex_string()));
+    map.insert(
+        "pubkey".into(),
+        Dynamic::from(event.pubkey.as_hex_string()),
+    );
+    map.insert("kind".into(), Dynamic::from(u32::from(event.kind) as i64));
+    map.insert("content".into(), Dynamic::from(event.content.clone()));
+    map
+}
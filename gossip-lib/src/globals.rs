@@ -1,10 +1,13 @@
+use crate::approval::{ApprovalPolicy, Decision};
 use crate::comms::{RelayJob, ToMinionMessage, ToOverlordMessage};
 use crate::delegation::Delegation;
 use crate::error::Error;
 use crate::feed::Feed;
 use crate::fetcher::Fetcher;
+use crate::filter::FilterManager;
 use crate::gossip_identity::GossipIdentity;
 use crate::media::Media;
+use crate::metrics::Metrics;
 use crate::nip46::ParsedCommand;
 use crate::people::{People, Person};
 use crate::relay::Relay;
@@ -16,8 +19,7 @@ use gossip_relay_picker::{Direction, RelayPicker};
 use nostr_types::{Event, Id, PayRequestData, Profile, PublicKey, RelayUrl, UncheckedUrl};
 use parking_lot::RwLock as PRwLock;
 use regex::Regex;
-use rhai::{Engine, AST};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize};
 use tokio::sync::{broadcast, mpsc, Mutex, Notify, RwLock};
 
@@ -31,6 +33,23 @@ pub enum ZapState {
     ReadyToPay(Id, String), // String is the Zap Invoice as a string, to be shown as a QR code
 }
 
+/// The most relays a single event will be posted to. Bounds the set-cover in
+/// [`Globals::relays_for_event`] so we never blast an event at an unbounded
+/// number of relays. Our own WRITE relays count toward the cap but are never
+/// dropped: if they alone exceed it, all of them are used and no extra relays
+/// are added to reach tagged recipients.
+const MAX_RELAYS_PER_EVENT: usize = 12;
+
+/// A relay chosen to carry an event, together with the tagged recipients it
+/// covers. Lets the UI explain deliverability ("this relay reaches Alice and
+/// Bob"). A relay chosen only because it is one of our own WRITE relays
+/// carries an empty `covers` list.
+#[derive(Debug, Clone)]
+pub struct RelayCoverage {
+    pub relay: RelayUrl,
+    pub covers: Vec<PublicKey>,
+}
+
 /// Global data shared between threads. Access via the static ref `GLOBALS`.
 pub struct Globals {
     /// This is a broadcast channel. All Minions should listen on it.
@@ -81,8 +100,9 @@ pub struct Globals {
     /// UI status messages
     pub status_queue: PRwLock<StatusQueue>,
 
-    /// How many data bytes have been read from the network, not counting overhead
-    pub bytes_read: AtomicUsize,
+    /// Per-relay network and subscription metrics (bytes, events, duplicates,
+    /// subscriptions, auth attempts, reconnects) plus moving-rate estimates
+    pub metrics: Metrics,
 
     /// How many subscriptions are open and not yet at EOSE
     pub open_subscriptions: AtomicUsize,
@@ -110,8 +130,9 @@ pub struct Globals {
     /// UI invalidate all
     pub ui_invalidate_all: AtomicBool,
 
-    /// Current zap data, for UI
-    pub current_zap: PRwLock<ZapState>,
+    /// Zaps currently in flight, keyed by the event being zapped, for UI.
+    /// Several zaps can progress through the pipeline simultaneously.
+    pub current_zaps: DashMap<Id, ZapState>,
 
     /// Hashtag regex
     pub hashtag_regex: Regex,
@@ -122,12 +143,8 @@ pub struct Globals {
     /// LMDB storage
     pub storage: Storage,
 
-    /// Events Processed
-    pub events_processed: AtomicU32,
-
-    /// Filter
-    pub(crate) filter_engine: Engine,
-    pub(crate) filter: Option<AST>,
+    /// Content-filter scripts (hot-reloadable, selectable per feed)
+    pub filter: FilterManager,
 
     // Wait for login
     pub wait_for_login: AtomicBool,
@@ -147,6 +164,9 @@ pub struct Globals {
 
     // nip46 approval requests
     pub nip46_approval_requests: PRwLock<Vec<(PublicKey, ParsedCommand)>>,
+
+    /// Persistent approval-policy layer, consulted before a prompt is enqueued
+    pub approval_policy: ApprovalPolicy,
 }
 
 lazy_static! {
@@ -164,8 +184,7 @@ lazy_static! {
             Err(e) => panic!("{e}")
         };
 
-        let filter_engine = Engine::new();
-        let filter = crate::filter::load_script(&filter_engine);
+        let filter = FilterManager::new();
 
         Globals {
             to_minions,
@@ -184,7 +203,7 @@ lazy_static! {
             status_queue: PRwLock::new(StatusQueue::new(
                 "Welcome to Gossip. Status messages will appear here. Click them to dismiss them.".to_owned()
             )),
-            bytes_read: AtomicUsize::new(0),
+            metrics: Metrics::new(),
             open_subscriptions: AtomicUsize::new(0),
             delegation: Delegation::default(),
             media: Media::new(),
@@ -195,12 +214,10 @@ lazy_static! {
             ui_notes_to_invalidate: PRwLock::new(Vec::new()),
             ui_people_to_invalidate: PRwLock::new(Vec::new()),
             ui_invalidate_all: AtomicBool::new(false),
-            current_zap: PRwLock::new(ZapState::None),
+            current_zaps: DashMap::new(),
             hashtag_regex: Regex::new(r"(?:^|\W)(#[\w\p{Extended_Pictographic}]+)(?:$|\W)").unwrap(),
             tagging_regex: Regex::new(r"(?:^|\s+)@([\w\p{Extended_Pictographic}]+)(?:$|\W)").unwrap(),
             storage,
-            events_processed: AtomicU32::new(0),
-            filter_engine,
             filter,
             wait_for_login: AtomicBool::new(false),
             wait_for_login_notify: Notify::new(),
@@ -209,6 +226,7 @@ lazy_static! {
             connect_requests: PRwLock::new(Vec::new()),
             auth_requests: PRwLock::new(Vec::new()),
             nip46_approval_requests: PRwLock::new(Vec::new()),
+            approval_policy: ApprovalPolicy::new(),
         }
     };
 }
@@ -243,56 +261,213 @@ impl Globals {
         Some(profile)
     }
 
-    // Which relays should an event be posted to (that it hasn't already been
-    // seen on)?
-    pub fn relays_for_event(event: &Event) -> Result<Vec<RelayUrl>, Error> {
+    /// Handle an incoming NIP-46 request. Consults the stored approval policy
+    /// first and only enqueues a prompt (into `nip46_approval_requests`) when
+    /// no rule matches. Returns the decision so the caller can act on an
+    /// auto-approve / auto-deny without waiting on the user.
+    pub fn consult_nip46_policy(remote: PublicKey, command: ParsedCommand) -> Decision {
+        let decision = GLOBALS.approval_policy.consult_nip46(remote, &command);
+        if decision == Decision::Ask {
+            GLOBALS
+                .nip46_approval_requests
+                .write()
+                .push((remote, command));
+        }
+        decision
+    }
+
+    /// Handle an incoming relay AUTH request. Consults the stored policy and
+    /// only enqueues a prompt (into `auth_requests`) when no rule matches.
+    pub fn consult_auth_policy(relay: RelayUrl) -> Decision {
+        let decision = GLOBALS.approval_policy.consult_auth(&relay);
+        if decision == Decision::Ask {
+            GLOBALS.auth_requests.write().push(relay);
+        }
+        decision
+    }
+
+    /// Handle an incoming relay connect request. Consults the stored policy
+    /// and only enqueues a prompt (into `connect_requests`) when no rule
+    /// matches.
+    pub fn consult_connect_policy(relay: RelayUrl, jobs: Vec<RelayJob>) -> Decision {
+        let decision = GLOBALS.approval_policy.consult_connect(&relay);
+        if decision == Decision::Ask {
+            GLOBALS.connect_requests.write().push((relay, jobs));
+        }
+        decision
+    }
+
+    /// Begin a new zap on the given event, placing it into the
+    /// `CheckingLnurl` state. Any existing zap on the same event is replaced.
+    pub fn begin_zap(id: Id, pubkey: PublicKey, lnurl: UncheckedUrl) {
+        GLOBALS
+            .current_zaps
+            .insert(id, ZapState::CheckingLnurl(id, pubkey, lnurl));
+    }
+
+    /// Advance an in-flight zap to its next state. Does nothing if no zap is
+    /// in flight for that event (it has completed or was never started).
+    pub fn advance_zap(id: Id, state: ZapState) {
+        if let ZapState::None = state {
+            GLOBALS.current_zaps.remove(&id);
+            return;
+        }
+        if let Some(mut entry) = GLOBALS.current_zaps.get_mut(&id) {
+            *entry = state;
+        }
+    }
+
+    /// Finish (or abandon) the zap on the given event, removing it from the
+    /// set of pending zaps.
+    pub fn complete_zap(id: Id) {
+        GLOBALS.current_zaps.remove(&id);
+    }
+
+    /// The current state of the zap on the given event, if one is in flight.
+    pub fn zap_state(id: Id) -> Option<ZapState> {
+        GLOBALS.current_zaps.get(&id).map(|e| e.value().clone())
+    }
+
+    /// All zaps currently in flight, for the UI to render as a list of
+    /// pending invoices / QR codes.
+    pub fn pending_zaps() -> Vec<(Id, ZapState)> {
+        GLOBALS
+            .current_zaps
+            .iter()
+            .map(|e| (*e.key(), e.value().clone()))
+            .collect()
+    }
+
+    // Which relays should an event be posted to, and which tagged recipients
+    // does each one reach?
+    //
+    // Rather than blasting the event at every tagged person's read relays, we
+    // compute a minimal covering set (outbox model): a greedy set-cover that
+    // repeatedly picks the relay reaching the most still-uncovered recipients,
+    // breaking ties by relay rank/health. This keeps posting bandwidth-
+    // efficient while still reaching every recipient on at least one relay
+    // where possible. Our own WRITE relays are always included so the event
+    // lands in our outbox.
+    pub fn relays_for_event(event: &Event) -> Result<Vec<RelayCoverage>, Error> {
         let num_relays_per_person = GLOBALS.storage.read_setting_num_relays_per_person();
-        let mut relay_urls: Vec<RelayUrl> = Vec::new();
 
-        // Get all of the relays that we write to
-        let write_relay_urls: Vec<RelayUrl> = GLOBALS
+        // Relays this event has already been seen on; never worth re-posting.
+        let seen_on: Vec<RelayUrl> = GLOBALS
             .storage
-            .filter_relays(|r| r.has_usage_bits(Relay::WRITE) && r.rank != 0)?
+            .get_event_seen_on_relay(event.id)?
             .iter()
-            .map(|relay| relay.url.clone())
+            .map(|(url, _time)| url.to_owned())
             .collect();
-        relay_urls.extend(write_relay_urls);
 
-        // Get 'read' relays for everybody tagged in the event.
-        let mut tagged_pubkeys: Vec<PublicKey> = event
+        let mut coverage: Vec<RelayCoverage> = Vec::new();
+
+        // Always post to our own WRITE relays so the event is in our outbox.
+        for relay in GLOBALS
+            .storage
+            .filter_relays(|r| r.has_usage_bits(Relay::WRITE) && r.rank != 0)?
+        {
+            if seen_on.contains(&relay.url) {
+                continue;
+            }
+            coverage.push(RelayCoverage {
+                relay: relay.url.clone(),
+                covers: Vec::new(),
+            });
+        }
+
+        // The recipients we must reach.
+        let tagged_pubkeys: Vec<PublicKey> = event
             .tags
             .iter()
-            .filter_map(|t| {
-                if let Ok((pubkey, _, _)) = t.parse_pubkey() {
-                    Some(pubkey)
-                } else {
-                    None
-                }
-            })
+            .filter_map(|t| t.parse_pubkey().ok().map(|(pubkey, _, _)| pubkey))
             .collect();
-        for pubkey in tagged_pubkeys.drain(..) {
-            let best_relays: Vec<RelayUrl> = GLOBALS
-                .storage
-                .get_best_relays(pubkey, Direction::Read)?
-                .drain(..)
-                .take(num_relays_per_person as usize + 1)
-                .map(|(u, _)| u)
-                .collect();
-            relay_urls.extend(best_relays);
+
+        // For each candidate relay, which recipients it reaches and its best
+        // rank/health score (used to break ties in the set-cover).
+        let mut relay_reach: HashMap<RelayUrl, (HashSet<PublicKey>, u64)> = HashMap::new();
+        for pubkey in &tagged_pubkeys {
+            let best = GLOBALS.storage.get_best_relays(*pubkey, Direction::Read)?;
+            for (url, score) in best.into_iter().take(num_relays_per_person as usize + 1) {
+                if seen_on.contains(&url) {
+                    continue;
+                }
+                let entry = relay_reach
+                    .entry(url)
+                    .or_insert_with(|| (HashSet::new(), 0));
+                entry.0.insert(*pubkey);
+                entry.1 = entry.1.max(score);
+            }
         }
 
-        // Remove all the 'seen_on' relays for this event
-        let seen_on: Vec<RelayUrl> = GLOBALS
-            .storage
-            .get_event_seen_on_relay(event.id)?
-            .iter()
-            .map(|(url, _time)| url.to_owned())
-            .collect();
-        relay_urls.retain(|r| !seen_on.contains(r));
+        let mut uncovered: HashSet<PublicKey> = tagged_pubkeys.iter().copied().collect();
+
+        // A WRITE relay already in `coverage` may also be some recipient's best
+        // read relay. Fold that reach into the existing entry rather than
+        // leaving it in `relay_reach`, where the set-cover below would emit a
+        // second entry for the same relay and we would double-post.
+        for rc in coverage.iter_mut() {
+            if let Some((recipients, _score)) = relay_reach.remove(&rc.relay) {
+                for pubkey in &recipients {
+                    uncovered.remove(pubkey);
+                }
+                rc.covers = recipients.into_iter().collect();
+            }
+        }
 
-        relay_urls.sort();
-        relay_urls.dedup();
+        // Greedy set-cover: repeatedly take the relay covering the most
+        // still-uncovered recipients, breaking ties by higher rank/health.
+        // Stop once we hit the relay cap, so a post with many tagged recipients
+        // does not fan out to an unbounded number of relays. Because the loop
+        // always takes the highest-covering, highest-ranked relay next, the
+        // relays we keep are the ones that reach the most recipients. Never cap
+        // below the mandatory WRITE relays already queued.
+        let cap = MAX_RELAYS_PER_EVENT.max(coverage.len());
+        while !uncovered.is_empty() {
+            if coverage.len() >= cap {
+                break;
+            }
+            let best = relay_reach
+                .iter()
+                .map(|(url, (recipients, score))| {
+                    let newly = recipients.intersection(&uncovered).count();
+                    (url.clone(), newly, *score)
+                })
+                .filter(|(_, newly, _)| *newly > 0)
+                .max_by(|a, b| a.1.cmp(&b.1).then(a.2.cmp(&b.2)));
+
+            let url = match best {
+                Some((url, _, _)) => url,
+                // Remaining recipients have no reachable read relay.
+                None => break,
+            };
+
+            let covered: Vec<PublicKey> = relay_reach[&url]
+                .0
+                .intersection(&uncovered)
+                .copied()
+                .collect();
+            for pubkey in &covered {
+                uncovered.remove(pubkey);
+            }
+            coverage.push(RelayCoverage {
+                relay: url.clone(),
+                covers: covered,
+            });
+            relay_reach.remove(&url);
+        }
+
+        // Any recipients still uncovered either have no reachable read relay or
+        // were dropped because we hit the relay cap. Log how many, so reduced
+        // deliverability is not silent; the returned coverage lists only the
+        // recipients that were reached.
+        if !uncovered.is_empty() {
+            tracing::warn!(
+                "relays_for_event: {} tagged recipient(s) not reachable within the {}-relay cap",
+                uncovered.len(),
+                cap
+            );
+        }
 
-        Ok(relay_urls)
+        Ok(coverage)
     }
 }
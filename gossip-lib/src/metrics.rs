@@ -0,0 +1,208 @@
+use dashmap::DashMap;
+use nostr_types::RelayUrl;
+use parking_lot::RwLock as PRwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// The length of the sliding window over which moving-rate estimates
+/// (events/sec and bytes/sec) are computed.
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Per-relay counters and moving-rate estimates.
+///
+/// All counters are monotonic totals for the life of the process; the
+/// rate estimators expose short-term throughput over a sliding window so
+/// the UI can render a live per-relay table.
+#[derive(Debug, Default)]
+pub struct RelayMetrics {
+    /// Data bytes read from this relay, not counting overhead
+    pub bytes_read: AtomicU64,
+
+    /// Data bytes written to this relay, not counting overhead
+    pub bytes_written: AtomicU64,
+
+    /// Events received from this relay
+    pub events_received: AtomicU64,
+
+    /// Events received that we already had (duplicates)
+    pub duplicate_events: AtomicU64,
+
+    /// Subscriptions opened on this relay
+    pub subscriptions_opened: AtomicU64,
+
+    /// Subscriptions closed on this relay
+    pub subscriptions_closed: AtomicU64,
+
+    /// AUTH attempts made to this relay
+    pub auth_attempts: AtomicU64,
+
+    /// Times we have reconnected to this relay
+    pub reconnects: AtomicU64,
+
+    /// Sliding-window estimator for events/sec
+    events_rate: PRwLock<RateEstimator>,
+
+    /// Sliding-window estimator for bytes/sec
+    bytes_rate: PRwLock<RateEstimator>,
+}
+
+impl RelayMetrics {
+    /// The current moving estimate of events received per second.
+    pub fn events_per_sec(&self) -> f64 {
+        self.events_rate.write().rate()
+    }
+
+    /// The current moving estimate of bytes read per second.
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.bytes_rate.write().rate()
+    }
+}
+
+/// A simple sliding-window rate estimator. Samples older than
+/// [`RATE_WINDOW`] are discarded on each access.
+#[derive(Debug, Default)]
+struct RateEstimator {
+    samples: std::collections::VecDeque<(Instant, u64)>,
+}
+
+impl RateEstimator {
+    fn observe(&mut self, amount: u64) {
+        let now = Instant::now();
+        self.samples.push_back((now, amount));
+        self.trim(now);
+    }
+
+    fn rate(&mut self) -> f64 {
+        let now = Instant::now();
+        self.trim(now);
+        let sum: u64 = self.samples.iter().map(|(_, n)| *n).sum();
+
+        // Divide by the actual span the retained samples cover rather than the
+        // fixed window, so we don't under-report before the window has filled
+        // (at startup or just after a reconnect). Clamp to the window above
+        // and to a small floor below to avoid dividing by ~0 on the first
+        // sample.
+        let span = match self.samples.front() {
+            Some((oldest, _)) => now.duration_since(*oldest).min(RATE_WINDOW),
+            None => return 0.0,
+        };
+        let secs = span.as_secs_f64().max(0.1);
+        sum as f64 / secs
+    }
+
+    fn trim(&mut self, now: Instant) {
+        while let Some((when, _)) = self.samples.front() {
+            if now.duration_since(*when) > RATE_WINDOW {
+                self.samples.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+/// Per-relay network and subscription metrics.
+///
+/// Replaces the old global `bytes_read` / `events_processed` atomics with
+/// counters keyed by [`RelayUrl`], so the UI can render a live per-relay
+/// throughput table. Minions increment these as they read from the wire.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    per_relay: DashMap<RelayUrl, RelayMetrics>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Default::default()
+    }
+
+    /// Record bytes read from a relay.
+    pub fn add_bytes_read(&self, relay: &RelayUrl, bytes: u64) {
+        let entry = self.per_relay.entry(relay.clone()).or_default();
+        entry.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+        entry.bytes_rate.write().observe(bytes);
+    }
+
+    /// Record bytes written to a relay.
+    pub fn add_bytes_written(&self, relay: &RelayUrl, bytes: u64) {
+        self.per_relay
+            .entry(relay.clone())
+            .or_default()
+            .bytes_written
+            .fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Record an event received from a relay, noting whether it was a duplicate
+    /// of one we already had.
+    pub fn event_received(&self, relay: &RelayUrl, duplicate: bool) {
+        let entry = self.per_relay.entry(relay.clone()).or_default();
+        entry.events_received.fetch_add(1, Ordering::Relaxed);
+        if duplicate {
+            entry.duplicate_events.fetch_add(1, Ordering::Relaxed);
+        }
+        entry.events_rate.write().observe(1);
+    }
+
+    /// Record a subscription opened on a relay.
+    pub fn subscription_opened(&self, relay: &RelayUrl) {
+        self.per_relay
+            .entry(relay.clone())
+            .or_default()
+            .subscriptions_opened
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a subscription closed on a relay.
+    pub fn subscription_closed(&self, relay: &RelayUrl) {
+        self.per_relay
+            .entry(relay.clone())
+            .or_default()
+            .subscriptions_closed
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record an AUTH attempt to a relay.
+    pub fn auth_attempt(&self, relay: &RelayUrl) {
+        self.per_relay
+            .entry(relay.clone())
+            .or_default()
+            .auth_attempts
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a reconnection to a relay.
+    pub fn reconnect(&self, relay: &RelayUrl) {
+        self.per_relay
+            .entry(relay.clone())
+            .or_default()
+            .reconnects
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Total data bytes read across all relays.
+    pub fn total_bytes_read(&self) -> u64 {
+        self.per_relay
+            .iter()
+            .map(|e| e.value().bytes_read.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Total events received across all relays.
+    pub fn total_events_received(&self) -> u64 {
+        self.per_relay
+            .iter()
+            .map(|e| e.value().events_received.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Run a closure against the metrics for a single relay, if we have any.
+    pub fn with_relay<T>(&self, relay: &RelayUrl, f: impl FnOnce(&RelayMetrics) -> T) -> Option<T> {
+        self.per_relay.get(relay).map(|e| f(e.value()))
+    }
+
+    /// The set of relays we currently hold metrics for, so the UI can
+    /// iterate the throughput table.
+    pub fn relays(&self) -> Vec<RelayUrl> {
+        self.per_relay.iter().map(|e| e.key().clone()).collect()
+    }
+}